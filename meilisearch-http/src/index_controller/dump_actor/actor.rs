@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
@@ -9,23 +10,44 @@ use chrono::Utc;
 use futures::{lock::Mutex, stream::StreamExt};
 use log::{error, info};
 use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::task::{AbortHandle, JoinHandle};
 use update_actor::UpdateActorHandle;
+use uuid::Uuid;
 use uuid_resolver::UuidResolverHandle;
 
-use super::{DumpError, DumpInfo, DumpMsg, DumpResult, DumpStatus, DumpTask};
+use super::{
+    CompressionOpts, DumpError, DumpInfo, DumpMsg, DumpProgress, DumpResult, DumpStatus, DumpTask,
+};
 use crate::index_controller::{update_actor, uuid_resolver};
 
 pub const CONCURRENT_DUMP_MSG: usize = 10;
 
 pub struct DumpActor<UuidResolver, Update> {
     inbox: Option<mpsc::Receiver<DumpMsg>>,
+    /// Weak handle used to send self-originated messages (progress updates, scheduled
+    /// `CreateDump`s) back into our own inbox. It MUST stay weak: a strong `Sender` held here
+    /// would never be dropped while the actor is alive, so the inbox could never observe every
+    /// sender going away and `run` would never return, even after every external handle is
+    /// dropped.
+    sender: mpsc::WeakSender<DumpMsg>,
     uuid_resolver: UuidResolver,
     update: Update,
     dump_path: PathBuf,
     lock: Arc<Mutex<()>>,
     dump_infos: Arc<RwLock<HashMap<String, DumpInfo>>>,
+    /// Abort handles for dumps that are currently running, keyed by uid, so that
+    /// `DumpMsg::AbortDump` can cancel one without tearing down the whole actor.
+    running_dumps: Arc<RwLock<HashMap<String, AbortHandle>>>,
     update_db_size: u64,
     index_db_size: u64,
+    /// When set, a background task triggers a dump on this interval without an external request.
+    schedule_interval: Option<Duration>,
+    /// When set, only the `max_retained_dumps` most recent dump files are kept after each
+    /// successful dump; older ones are pruned from `dump_path`.
+    max_retained_dumps: Option<usize>,
+    /// Handle of the background scheduler task, if `schedule_interval` is set. Aborted once the
+    /// actor's main loop exits so it doesn't keep running past actor shutdown.
+    scheduler_handle: Option<JoinHandle<()>>,
 }
 
 /// Generate uid from creation date
@@ -33,6 +55,19 @@ fn generate_uid() -> String {
     Utc::now().format("%Y%m%d-%H%M%S%3f").to_string()
 }
 
+const DUMP_ARCHIVE_EXTENSIONS: [&str; 3] = [".dump", ".dump.gz", ".dump.zst"];
+
+/// Whether `path` names a dump archive (as opposed to a loose per-index artifact).
+fn is_dump_archive(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| {
+            DUMP_ARCHIVE_EXTENSIONS
+                .iter()
+                .any(|ext| name.ends_with(ext))
+        })
+}
+
 impl<UuidResolver, Update> DumpActor<UuidResolver, Update>
 where
     UuidResolver: UuidResolverHandle + Send + Sync + Clone + 'static,
@@ -40,29 +75,42 @@ where
 {
     pub fn new(
         inbox: mpsc::Receiver<DumpMsg>,
+        sender: mpsc::Sender<DumpMsg>,
         uuid_resolver: UuidResolver,
         update: Update,
         dump_path: impl AsRef<Path>,
         index_db_size: u64,
         update_db_size: u64,
+        schedule_interval: Option<Duration>,
+        max_retained_dumps: Option<usize>,
     ) -> Self {
         let dump_infos = Arc::new(RwLock::new(HashMap::new()));
+        let running_dumps = Arc::new(RwLock::new(HashMap::new()));
         let lock = Arc::new(Mutex::new(()));
         Self {
             inbox: Some(inbox),
+            sender: sender.downgrade(),
             uuid_resolver,
             update,
             dump_path: dump_path.as_ref().into(),
             dump_infos,
+            running_dumps,
             lock,
             index_db_size,
             update_db_size,
+            schedule_interval,
+            max_retained_dumps,
+            scheduler_handle: None,
         }
     }
 
     pub async fn run(mut self) {
         info!("Started dump actor.");
 
+        if let Some(interval) = self.schedule_interval {
+            self.scheduler_handle = Some(Self::spawn_scheduler(self.sender.clone(), interval));
+        }
+
         let mut inbox = self
             .inbox
             .take()
@@ -81,25 +129,70 @@ where
             .for_each_concurrent(Some(CONCURRENT_DUMP_MSG), |msg| self.handle_message(msg))
             .await;
 
+        if let Some(handle) = self.scheduler_handle.take() {
+            handle.abort();
+        }
+
         error!("Dump actor stopped.");
     }
 
+    /// Periodically requests a dump, as if an external client had called `handle_create_dump`.
+    /// The first tick is consumed immediately so the first automatic dump happens after one full
+    /// `interval`, not on startup.
+    fn spawn_scheduler(sender: mpsc::WeakSender<DumpMsg>, interval: Duration) -> JoinHandle<()> {
+        tokio::task::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+
+                let sender = match sender.upgrade() {
+                    Some(sender) => sender,
+                    None => break,
+                };
+
+                let (ret, rx) = oneshot::channel();
+                let msg = DumpMsg::CreateDump {
+                    compression: CompressionOpts::default(),
+                    ret,
+                };
+                if sender.send(msg).await.is_err() {
+                    break;
+                }
+                // Keep the receiver alive until handle_create_dump responds: dropping it
+                // immediately would make that response's `ret.send(..).expect(..)` panic.
+                let _ = rx.await;
+            }
+        })
+    }
+
     async fn handle_message(&self, msg: DumpMsg) {
         use DumpMsg::*;
 
         match msg {
-            CreateDump { ret } => {
-                let _ = self.handle_create_dump(ret).await;
+            CreateDump { compression, ret } => {
+                let _ = self.handle_create_dump(compression, ret).await;
             }
             DumpInfo { ret, uid } => {
                 let _ = ret.send(self.handle_dump_info(uid).await);
             }
+            UpdateProgress { uid, progress } => {
+                self.handle_update_progress(uid, progress).await;
+            }
+            AbortDump { uid, ret } => {
+                let _ = ret.send(self.handle_abort_dump(uid).await);
+            }
         }
     }
 
-    async fn handle_create_dump(&self, ret: oneshot::Sender<DumpResult<DumpInfo>>) {
+    async fn handle_create_dump(
+        &self,
+        compression: CompressionOpts,
+        ret: oneshot::Sender<DumpResult<DumpInfo>>,
+    ) {
         let uid = generate_uid();
-        let info = DumpInfo::new(uid.clone(), DumpStatus::InProgress);
+        let info = DumpInfo::new(uid.clone(), DumpStatus::InProgress, compression.codec);
 
         let _lock = match self.lock.try_lock() {
             Some(lock) => lock,
@@ -117,16 +210,43 @@ where
 
         ret.send(Ok(info)).expect("Dump actor is dead");
 
+        // Captured once, up front, rather than re-queried on abort: that way cleanup always
+        // targets exactly the indexes this dump was scheduled against, even if one is deleted
+        // before the abort is processed.
+        let indexes = match self.uuid_resolver.list().await {
+            Ok(indexes) => indexes,
+            Err(e) => {
+                let mut dump_infos = self.dump_infos.write().await;
+                let dump_infos = dump_infos
+                    .get_mut(&uid)
+                    .expect("dump entry deleted while lock was acquired");
+                dump_infos.with_error(e.to_string());
+                error!("Dump failed: {}", e);
+                return;
+            }
+        };
+        let index_uuids: Vec<Uuid> = indexes.iter().map(|(_, uuid)| *uuid).collect();
+
         let task = DumpTask {
             path: self.dump_path.clone(),
-            uuid_resolver: self.uuid_resolver.clone(),
+            indexes,
             update_handle: self.update.clone(),
             uid: uid.clone(),
             update_db_size: self.update_db_size,
             index_db_size: self.index_db_size,
+            compression,
+            progress_sender: self.sender.clone(),
         };
 
-        let task_result = tokio::task::spawn(task.run()).await;
+        let handle = tokio::task::spawn(task.run());
+        self.running_dumps
+            .write()
+            .await
+            .insert(uid.clone(), handle.abort_handle());
+
+        let task_result = handle.await;
+
+        self.running_dumps.write().await.remove(&uid);
 
         let mut dump_infos = self.dump_infos.write().await;
         let dump_infos = dump_infos
@@ -137,16 +257,26 @@ where
             Ok(Ok(())) => {
                 dump_infos.done();
                 info!("Dump succeed");
+                self.enforce_retention();
             }
             Ok(Err(e)) => {
                 dump_infos.with_error(e.to_string());
                 error!("Dump failed: {}", e);
+                Self::cleanup_dump_artifacts(&self.dump_path, &uid, &index_uuids);
+            }
+            Err(e) if e.is_cancelled() => {
+                dump_infos.aborted();
+                Self::cleanup_dump_artifacts(&self.dump_path, &uid, &index_uuids);
+                info!("Dump {} aborted", uid);
             }
             Err(_) => {
                 dump_infos.with_error("Unexpected error while performing dump.".to_string());
                 error!("Dump panicked. Dump status set to failed");
+                Self::cleanup_dump_artifacts(&self.dump_path, &uid, &index_uuids);
             }
         };
+        // `_lock` is dropped here, releasing it whether the dump finished, failed, or was
+        // aborted, so a new dump can always be started afterwards.
     }
 
     async fn handle_dump_info(&self, uid: String) -> DumpResult<DumpInfo> {
@@ -155,4 +285,296 @@ where
             _ => Err(DumpError::DumpDoesNotExist(uid)),
         }
     }
+
+    async fn handle_update_progress(&self, uid: String, progress: DumpProgress) {
+        if let Some(info) = self.dump_infos.write().await.get_mut(&uid) {
+            info.record_progress(progress);
+        }
+    }
+
+    async fn handle_abort_dump(&self, uid: String) -> DumpResult<()> {
+        match self.running_dumps.read().await.get(&uid) {
+            Some(handle) => {
+                handle.abort();
+                Ok(())
+            }
+            None => Err(DumpError::DumpDoesNotExist(uid)),
+        }
+    }
+
+    /// Deletes the oldest dump archives in `dump_path` once there are more than
+    /// `max_retained_dumps` of them. Only archives are considered (see `is_dump_archive`), so
+    /// any loose per-index artifact from an in-flight dump is never mistaken for one. Dump
+    /// filenames are timestamp-based (see `generate_uid`), so a lexicographic sort is also a
+    /// chronological one.
+    fn enforce_retention(&self) {
+        let max_retained_dumps = match self.max_retained_dumps {
+            Some(max) => max,
+            None => return,
+        };
+
+        let entries = match std::fs::read_dir(&self.dump_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!(
+                    "Could not read dump directory {}: {}",
+                    self.dump_path.display(),
+                    e
+                );
+                return;
+            }
+        };
+
+        let mut paths: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| is_dump_archive(path))
+            .collect();
+        paths.sort();
+
+        if paths.len() <= max_retained_dumps {
+            return;
+        }
+
+        for path in &paths[..paths.len() - max_retained_dumps] {
+            let result = if path.is_dir() {
+                std::fs::remove_dir_all(path)
+            } else {
+                std::fs::remove_file(path)
+            };
+
+            if let Err(e) = result {
+                error!("Could not remove old dump {}: {}", path.display(), e);
+            } else {
+                info!(
+                    "Removed old dump {} to respect retention limit",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    /// Removes any file left behind by a dump that didn't complete normally, whether aborted,
+    /// failed, or panicked: the archive, plus the per-index artifacts at
+    /// `dump_path/<index_uuid>` for every index this dump covered at start time (`index_uuids`),
+    /// whether or not that index ever reported progress.
+    fn cleanup_dump_artifacts(dump_path: &Path, uid: &str, index_uuids: &[Uuid]) {
+        let entries = match std::fs::read_dir(dump_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!(
+                    "Could not read dump directory {}: {}",
+                    dump_path.display(),
+                    e
+                );
+                return;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // Dump archives are named `<uid>.<ext>`, where `<ext>` can itself contain a dot
+            // (e.g. `dump.gz`), so match on the uid prefix rather than the file stem.
+            let belongs_to_this_dump = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| name.starts_with(uid));
+
+            if belongs_to_this_dump {
+                super::remove_dump_artifact(&path);
+            }
+        }
+
+        for index_uuid in index_uuids {
+            super::remove_dump_artifact(&dump_path.join(index_uuid.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use tokio::sync::Notify;
+
+    use super::*;
+    use crate::index_controller::dump_actor::IndexDumpStatus;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("mock handle error")]
+    struct MockError;
+
+    #[derive(Clone)]
+    struct MockUuidResolver {
+        indexes: Vec<(String, Uuid)>,
+    }
+
+    #[async_trait]
+    impl UuidResolverHandle for MockUuidResolver {
+        async fn list(&self) -> Result<Vec<(String, Uuid)>, MockError> {
+            Ok(self.indexes.clone())
+        }
+    }
+
+    /// Writes a marker file named after the index uuid under the dump path, mimicking the real
+    /// per-index dump step. `stall_on`, if set, notifies `reached_stall` right after writing that
+    /// one index's marker file and then blocks until `stall` is notified back, so a test can wait
+    /// for a deterministic "this index has partial artifacts on disk and is still in progress"
+    /// point before acting, instead of racing a fixed sleep.
+    #[derive(Clone)]
+    struct MockUpdateHandle {
+        stall_on: Option<Uuid>,
+        reached_stall: Arc<Notify>,
+        stall: Arc<Notify>,
+    }
+
+    #[async_trait]
+    impl UpdateActorHandle for MockUpdateHandle {
+        async fn dump(&self, uuid: Uuid, path: PathBuf) -> Result<(), MockError> {
+            std::fs::create_dir_all(&path).unwrap();
+            std::fs::write(path.join(uuid.to_string()), b"data").unwrap();
+            if self.stall_on == Some(uuid) {
+                self.reached_stall.notify_one();
+                self.stall.notified().await;
+            }
+            Ok(())
+        }
+    }
+
+    fn make_actor(
+        indexes: Vec<(String, Uuid)>,
+        dump_path: PathBuf,
+        stall_on: Option<Uuid>,
+        max_retained_dumps: Option<usize>,
+    ) -> (
+        DumpActor<MockUuidResolver, MockUpdateHandle>,
+        Arc<Notify>,
+        Arc<Notify>,
+    ) {
+        let (sender, inbox) = mpsc::channel(10);
+        let reached_stall = Arc::new(Notify::new());
+        let stall = Arc::new(Notify::new());
+        let actor = DumpActor::new(
+            inbox,
+            sender,
+            MockUuidResolver { indexes },
+            MockUpdateHandle {
+                stall_on,
+                reached_stall: reached_stall.clone(),
+                stall: stall.clone(),
+            },
+            dump_path,
+            100,
+            100,
+            None,
+            max_retained_dumps,
+        );
+        (actor, reached_stall, stall)
+    }
+
+    fn temp_dump_path() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dump_actor_test_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn dump_marks_each_index_done_as_it_finishes_and_records_bytes_written() {
+        let dir = temp_dump_path();
+        let indexes = vec![
+            ("one".to_string(), Uuid::new_v4()),
+            ("two".to_string(), Uuid::new_v4()),
+        ];
+        let (mut actor, _reached_stall, _stall) = make_actor(indexes, dir.clone(), None, None);
+        // Progress updates travel through the actor's inbox, which only `run` drains; route them
+        // to a channel this test drains by hand instead.
+        let (sender, mut inbox) = mpsc::channel(10);
+        actor.sender = sender.downgrade();
+
+        let (ret, rx) = oneshot::channel();
+        actor
+            .handle_create_dump(CompressionOpts::default(), ret)
+            .await;
+        let info = rx.await.unwrap().unwrap();
+        while let Ok(msg) = inbox.try_recv() {
+            actor.handle_message(msg).await;
+        }
+
+        let info = actor.handle_dump_info(info.uid).await.unwrap();
+        assert_eq!(info.status, DumpStatus::Done);
+        assert_eq!(info.indexes.len(), 2);
+        assert!(info
+            .indexes
+            .values()
+            .all(|status| *status == IndexDumpStatus::Done));
+        // Each index's mock artifact is the 4-byte literal `b"data"` written by
+        // `MockUpdateHandle::dump`; `bytes_written` is measured from the real files on disk.
+        assert_eq!(info.bytes_written, Some(8));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn aborting_a_dump_removes_artifacts_for_the_index_still_in_flight() {
+        let dir = temp_dump_path();
+        // The second index is still being dumped (and has already written its marker file) when
+        // the abort lands, so it never gets to send an UpdateProgress for itself. Cleanup must
+        // still find and remove its artifacts.
+        let stalled_uuid = Uuid::new_v4();
+        let indexes = vec![
+            ("one".to_string(), Uuid::new_v4()),
+            ("two".to_string(), stalled_uuid),
+        ];
+        let (actor, reached_stall, stall) =
+            make_actor(indexes, dir.clone(), Some(stalled_uuid), None);
+        let actor = Arc::new(actor);
+
+        let (ret, rx) = oneshot::channel();
+        let actor_clone = actor.clone();
+        let create_fut = tokio::task::spawn(async move {
+            actor_clone
+                .handle_create_dump(CompressionOpts::default(), ret)
+                .await;
+        });
+
+        let info = rx.await.unwrap().unwrap();
+        reached_stall.notified().await;
+        actor.handle_abort_dump(info.uid.clone()).await.unwrap();
+        stall.notify_one();
+        create_fut.await.unwrap();
+
+        let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().flatten().collect();
+        assert!(entries.is_empty());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn enforce_retention_keeps_only_the_most_recent_dumps() {
+        let dir = temp_dump_path();
+        for name in [
+            "20200101-000000000.dump",
+            "20200102-000000000.dump",
+            "20200103-000000000.dump",
+        ] {
+            std::fs::write(dir.join(name), b"x").unwrap();
+        }
+
+        let (actor, _reached_stall, _stall) = make_actor(vec![], dir.clone(), None, Some(2));
+        actor.enforce_retention();
+
+        let mut remaining: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .flatten()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec!["20200102-000000000.dump", "20200103-000000000.dump"]
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
 }
@@ -0,0 +1,22 @@
+use tokio::sync::oneshot;
+
+use super::{CompressionOpts, DumpInfo, DumpProgress, DumpResult};
+
+#[derive(Debug)]
+pub enum DumpMsg {
+    CreateDump {
+        compression: CompressionOpts,
+        ret: oneshot::Sender<DumpResult<DumpInfo>>,
+    },
+    DumpInfo {
+        uid: String,
+        ret: oneshot::Sender<DumpResult<DumpInfo>>,
+    },
+    /// Sent by a running `DumpTask` to report how far along it is. This message carries no
+    /// response channel: the actor simply folds the progress into the matching `DumpInfo`.
+    UpdateProgress { uid: String, progress: DumpProgress },
+    AbortDump {
+        uid: String,
+        ret: oneshot::Sender<DumpResult<()>>,
+    },
+}
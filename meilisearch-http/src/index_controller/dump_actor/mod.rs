@@ -0,0 +1,480 @@
+mod actor;
+mod message;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use log::error;
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+pub use actor::{DumpActor, CONCURRENT_DUMP_MSG};
+pub use message::DumpMsg;
+
+use crate::index_controller::update_actor::UpdateActorHandle;
+
+pub type DumpResult<T> = std::result::Result<T, DumpError>;
+
+#[derive(Debug, Error)]
+pub enum DumpError {
+    #[error("A dump is already processing. You must wait until the current process is finished before requesting another dump.")]
+    DumpAlreadyRunning,
+    #[error("Dump `{0}` does not exist.")]
+    DumpDoesNotExist(String),
+    #[error("Internal error while performing dump: {0}")]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DumpStatus {
+    Done,
+    InProgress,
+    Aborted,
+    Failed,
+}
+
+/// How far along a single index is in the current dump.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexDumpStatus {
+    InProgress,
+    Done,
+}
+
+/// The archive format used to write a dump to disk.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionCodec {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Default for CompressionCodec {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl CompressionCodec {
+    /// File extension used for a dump archive written with this codec, appended to the dump uid.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::None => "dump",
+            Self::Gzip => "dump.gz",
+            Self::Zstd => "dump.zst",
+        }
+    }
+}
+
+/// Compression settings attached to a `CreateDump` request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressionOpts {
+    pub codec: CompressionCodec,
+    /// Codec-specific compression level. Ignored by `CompressionCodec::None`.
+    pub level: Option<u32>,
+}
+
+/// A `Write` implementation that dispatches to the codec picked by a `CompressionOpts`, so
+/// `DumpTask` can build its archive without matching on the codec at every write.
+enum CompressedWriter {
+    None(File),
+    Gzip(flate2::write::GzEncoder<File>),
+    Zstd(zstd::stream::write::Encoder<'static, File>),
+}
+
+impl CompressedWriter {
+    fn new(file: File, opts: CompressionOpts) -> DumpResult<Self> {
+        match opts.codec {
+            CompressionCodec::None => Ok(Self::None(file)),
+            CompressionCodec::Gzip => {
+                let level = flate2::Compression::new(opts.level.unwrap_or(6).min(9));
+                Ok(Self::Gzip(flate2::write::GzEncoder::new(file, level)))
+            }
+            CompressionCodec::Zstd => {
+                let level = opts.level.unwrap_or(3).min(22) as i32;
+                let encoder = zstd::stream::write::Encoder::new(file, level)
+                    .map_err(|e| DumpError::Internal(Box::new(e)))?;
+                Ok(Self::Zstd(encoder))
+            }
+        }
+    }
+
+    fn finish(self) -> DumpResult<()> {
+        match self {
+            Self::None(mut file) => file.flush().map_err(|e| DumpError::Internal(Box::new(e))),
+            Self::Gzip(encoder) => encoder
+                .finish()
+                .map(drop)
+                .map_err(|e| DumpError::Internal(Box::new(e))),
+            Self::Zstd(encoder) => encoder
+                .finish()
+                .map(drop)
+                .map_err(|e| DumpError::Internal(Box::new(e))),
+        }
+    }
+}
+
+impl Write for CompressedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::None(w) => w.write(buf),
+            Self::Gzip(w) => w.write(buf),
+            Self::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::None(w) => w.flush(),
+            Self::Gzip(w) => w.flush(),
+            Self::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+/// A progress update sent by a running `DumpTask` back to the `DumpActor`.
+#[derive(Debug, Clone)]
+pub struct DumpProgress {
+    pub indexes_done: usize,
+    pub indexes_total: usize,
+    pub bytes_written: u64,
+    pub index_uid: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DumpInfo {
+    pub uid: String,
+    pub status: DumpStatus,
+    /// Share of the dump that has completed so far, in the `[0.0, 1.0]` range. `None` until the
+    /// first progress update is received or the dump completes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f32>,
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    pub indexes: HashMap<String, IndexDumpStatus>,
+    /// Total number of bytes written so far, reported by the last progress update received.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_written: Option<u64>,
+    pub compression: CompressionCodec,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DumpInfo {
+    pub fn new(uid: String, status: DumpStatus, compression: CompressionCodec) -> Self {
+        Self {
+            uid,
+            status,
+            progress: None,
+            indexes: HashMap::new(),
+            bytes_written: None,
+            compression,
+            error: None,
+        }
+    }
+
+    pub fn with_error(&mut self, error: String) {
+        self.status = DumpStatus::Failed;
+        self.error = Some(error);
+    }
+
+    pub fn aborted(&mut self) {
+        self.status = DumpStatus::Aborted;
+    }
+
+    pub fn done(&mut self) {
+        self.status = DumpStatus::Done;
+        self.progress = Some(1.0);
+        for status in self.indexes.values_mut() {
+            *status = IndexDumpStatus::Done;
+        }
+    }
+
+    /// Folds in a progress update sent after an index's per-index dump step has completed, so
+    /// that index is recorded as `Done`, not `InProgress`.
+    pub fn record_progress(&mut self, progress: DumpProgress) {
+        self.indexes
+            .insert(progress.index_uid, IndexDumpStatus::Done);
+        self.bytes_written = Some(progress.bytes_written);
+        if progress.indexes_total > 0 {
+            self.progress = Some(progress.indexes_done as f32 / progress.indexes_total as f32);
+        }
+    }
+
+    pub fn dump_already_in_progress(&self) -> bool {
+        self.status == DumpStatus::InProgress
+    }
+}
+
+pub struct DumpTask<P> {
+    pub path: PathBuf,
+    /// The indexes this dump covers, captured once by the `DumpActor` before the task was
+    /// spawned, so the set stays fixed even if an index is deleted while the dump is running.
+    pub indexes: Vec<(String, Uuid)>,
+    pub update_handle: P,
+    pub uid: String,
+    pub update_db_size: u64,
+    pub index_db_size: u64,
+    pub compression: CompressionOpts,
+    /// Channel used to report progress back to the `DumpActor` that spawned this task. Weak so
+    /// that a running task never keeps the actor's inbox open by itself (see
+    /// `DumpActor::sender`).
+    pub progress_sender: mpsc::WeakSender<DumpMsg>,
+}
+
+impl<P> DumpTask<P>
+where
+    P: UpdateActorHandle + Send + Sync + Clone + 'static,
+{
+    pub async fn run(self) -> DumpResult<()> {
+        let indexes_total = self.indexes.len();
+        let mut bytes_written = 0u64;
+
+        for (indexes_done, (index_uid, index_uuid)) in self.indexes.iter().enumerate() {
+            self.update_handle
+                .dump(*index_uuid, self.path.clone())
+                .await
+                .map_err(|e| DumpError::Internal(Box::new(e)))?;
+            bytes_written += artifact_size(&self.path.join(index_uuid.to_string()));
+
+            let progress = DumpProgress {
+                indexes_done: indexes_done + 1,
+                indexes_total,
+                bytes_written,
+                index_uid: index_uid.clone(),
+            };
+            if let Some(sender) = self.progress_sender.upgrade() {
+                let _ = sender
+                    .send(DumpMsg::UpdateProgress {
+                        uid: self.uid.clone(),
+                        progress,
+                    })
+                    .await;
+            }
+        }
+
+        // Archive building is blocking I/O (tar entries, compression), so it runs on a blocking
+        // thread rather than stalling this task's async worker. Once started it always runs to
+        // completion even if this dump is aborted, since abort only stops the outer task from
+        // awaiting it, not the blocking thread itself.
+        tokio::task::spawn_blocking(move || self.write_archive())
+            .await
+            .map_err(|e| DumpError::Internal(Box::new(e)))??;
+
+        Ok(())
+    }
+
+    /// Builds the dump's archive: a tar file, compressed with the requested codec, containing
+    /// `metadata.json` plus the per-index artifacts `update_handle.dump` wrote under `self.path`
+    /// for each index in `self.indexes`. Those loose files are removed once folded into the
+    /// archive, so a successful dump doesn't leave them behind in `dump_path`. The archive is
+    /// named after the dump's uid, with an extension matching the codec (see
+    /// `CompressionCodec::extension`).
+    fn write_archive(self) -> DumpResult<()> {
+        let file =
+            File::create(self.archive_path()).map_err(|e| DumpError::Internal(Box::new(e)))?;
+        let mut builder = tar::Builder::new(CompressedWriter::new(file, self.compression)?);
+
+        let metadata = format!(
+            "{{\"dumpUid\":\"{}\",\"indexDbSize\":{},\"updateDbSize\":{}}}",
+            self.uid, self.index_db_size, self.update_db_size
+        );
+        let mut header = tar::Header::new_gnu();
+        header.set_size(metadata.len() as u64);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "metadata.json", metadata.as_bytes())
+            .map_err(|e| DumpError::Internal(Box::new(e)))?;
+
+        for (_, index_uuid) in &self.indexes {
+            let index_path = self.path.join(index_uuid.to_string());
+            if index_path.is_dir() {
+                builder
+                    .append_dir_all(index_uuid.to_string(), &index_path)
+                    .map_err(|e| DumpError::Internal(Box::new(e)))?;
+            } else {
+                let mut index_file =
+                    File::open(&index_path).map_err(|e| DumpError::Internal(Box::new(e)))?;
+                builder
+                    .append_file(index_uuid.to_string(), &mut index_file)
+                    .map_err(|e| DumpError::Internal(Box::new(e)))?;
+            }
+        }
+
+        builder
+            .into_inner()
+            .map_err(|e| DumpError::Internal(Box::new(e)))?
+            .finish()?;
+
+        for (_, index_uuid) in &self.indexes {
+            remove_dump_artifact(&self.path.join(index_uuid.to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn archive_path(&self) -> PathBuf {
+        self.path.join(format!(
+            "{}.{}",
+            self.uid,
+            self.compression.codec.extension()
+        ))
+    }
+}
+
+/// Total size on disk of a per-index artifact written by `update_handle.dump`, recursing into
+/// directories. Used to report real progress instead of a fixed per-index estimate.
+fn artifact_size(path: &Path) -> u64 {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| artifact_size(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Removes a file or directory written as part of a dump, ignoring a missing path.
+fn remove_dump_artifact(path: &Path) {
+    let result = if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+
+    if let Err(e) = result {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            error!(
+                "Could not remove dumped index artifact {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use async_trait::async_trait;
+
+    use super::*;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("mock handle error")]
+    struct MockError;
+
+    #[derive(Clone)]
+    struct MockUpdateHandle;
+
+    #[async_trait]
+    impl UpdateActorHandle for MockUpdateHandle {
+        async fn dump(&self, _uuid: Uuid, _path: PathBuf) -> Result<(), MockError> {
+            Ok(())
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dump_task_test_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn make_task(
+        path: PathBuf,
+        indexes: Vec<(String, Uuid)>,
+        compression: CompressionOpts,
+    ) -> DumpTask<MockUpdateHandle> {
+        DumpTask {
+            path,
+            indexes,
+            update_handle: MockUpdateHandle,
+            uid: "20200101-000000000".to_string(),
+            update_db_size: 10,
+            index_db_size: 20,
+            compression,
+            progress_sender: mpsc::channel(1).0.downgrade(),
+        }
+    }
+
+    /// Reads back every entry of a tar archive written with `codec`, keyed by entry path.
+    fn read_archive(path: &Path, codec: CompressionCodec) -> HashMap<String, Vec<u8>> {
+        let file = File::open(path).unwrap();
+        let reader: Box<dyn Read> = match codec {
+            CompressionCodec::None => Box::new(file),
+            CompressionCodec::Gzip => Box::new(flate2::read::GzDecoder::new(file)),
+            CompressionCodec::Zstd => Box::new(zstd::stream::read::Decoder::new(file).unwrap()),
+        };
+
+        let mut archive = tar::Archive::new(reader);
+        archive
+            .entries()
+            .unwrap()
+            .map(Result::unwrap)
+            .map(|mut entry| {
+                let name = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut contents = Vec::new();
+                entry.read_to_end(&mut contents).unwrap();
+                (name, contents)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn write_archive_packages_index_artifacts_and_removes_loose_files() {
+        let dir = temp_dir();
+        let index_uuid = Uuid::new_v4();
+        std::fs::write(dir.join(index_uuid.to_string()), b"index data").unwrap();
+
+        let task = make_task(
+            dir.clone(),
+            vec![("my-index".to_string(), index_uuid)],
+            CompressionOpts::default(),
+        );
+        task.write_archive().unwrap();
+
+        let archive_path = dir.join("20200101-000000000.dump");
+        assert!(archive_path.exists());
+        assert!(!dir.join(index_uuid.to_string()).exists());
+
+        let entries = read_archive(&archive_path, CompressionCodec::None);
+        assert_eq!(
+            entries.get(&index_uuid.to_string()).map(Vec::as_slice),
+            Some(b"index data".as_slice())
+        );
+        assert!(entries.contains_key("metadata.json"));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn write_archive_respects_compression_codec() {
+        for codec in [CompressionCodec::Gzip, CompressionCodec::Zstd] {
+            let dir = temp_dir();
+            let task = make_task(dir.clone(), vec![], CompressionOpts { codec, level: None });
+            task.write_archive().unwrap();
+
+            let archive_path = dir.join(format!("20200101-000000000.{}", codec.extension()));
+            assert!(archive_path.exists());
+            assert!(read_archive(&archive_path, codec).contains_key("metadata.json"));
+
+            std::fs::remove_dir_all(&dir).ok();
+        }
+    }
+}